@@ -0,0 +1,393 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::check_pin_uv_auth_protocol;
+use super::large_blobs::{MAX_MSG_SIZE, MIN_MSG_SIZE};
+use super::pin_protocol::{PinPermission, PinUvAuthProtocol};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The `authenticatorConfig` subcommands defined in CTAP 2.1 section 6.11,
+/// plus a vendor subcommand to tune `LargeBlobs`' fragment size at runtime
+/// instead of recompiling with a different `MAX_MSG_SIZE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConfigSubCommand {
+    EnableEnterpriseAttestation = 0x01,
+    ToggleAlwaysUv = 0x02,
+    SetMinPinLength = 0x03,
+    VendorSetMaxMsgSize = 0xFF,
+}
+
+impl ConfigSubCommand {
+    fn from_int(value: u64) -> Result<ConfigSubCommand, Ctap2StatusCode> {
+        match value {
+            0x01 => Ok(ConfigSubCommand::EnableEnterpriseAttestation),
+            0x02 => Ok(ConfigSubCommand::ToggleAlwaysUv),
+            0x03 => Ok(ConfigSubCommand::SetMinPinLength),
+            0xFF => Ok(ConfigSubCommand::VendorSetMaxMsgSize),
+            _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+        }
+    }
+}
+
+/// Parameters of the `authenticatorConfig` command (CTAP 2.1 section 6.11).
+pub struct AuthenticatorConfigParameters {
+    pub sub_command: u64,
+    pub sub_command_params: Option<Vec<u8>>,
+    pub min_pin_length: Option<u8>,
+    pub max_msg_size: Option<usize>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+/// Implements the logic for the `authenticatorConfig` command and keeps the
+/// state it reads/writes in `PersistentStore`.
+#[derive(Default)]
+pub struct AuthenticatorConfig {}
+
+impl AuthenticatorConfig {
+    pub fn new() -> AuthenticatorConfig {
+        AuthenticatorConfig {}
+    }
+
+    /// Process the authenticatorConfig command.
+    pub fn process_command(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        pin_uv_auth_protocol: &mut dyn PinUvAuthProtocol,
+        config_params: AuthenticatorConfigParameters,
+    ) -> Result<(), Ctap2StatusCode> {
+        let AuthenticatorConfigParameters {
+            sub_command,
+            sub_command_params,
+            min_pin_length,
+            max_msg_size,
+            pin_uv_auth_protocol: pin_uv_auth_protocol_id,
+            pin_uv_auth_param,
+        } = config_params;
+
+        let sub_command = ConfigSubCommand::from_int(sub_command)?;
+        let pin_uv_auth_param =
+            pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED)?;
+        check_pin_uv_auth_protocol(pin_uv_auth_protocol_id)?;
+        pin_uv_auth_protocol.has_permission(PinPermission::AuthenticatorConfiguration)?;
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0D, sub_command as u8]);
+        if let Some(sub_command_params) = sub_command_params.as_ref() {
+            message.extend(sub_command_params);
+        }
+        if !pin_uv_auth_protocol.verify_pin_auth_token(&message, &pin_uv_auth_param) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+
+        match sub_command {
+            ConfigSubCommand::EnableEnterpriseAttestation => {
+                persistent_store.enable_enterprise_attestation()?;
+            }
+            ConfigSubCommand::ToggleAlwaysUv => {
+                persistent_store.toggle_always_uv()?;
+            }
+            ConfigSubCommand::SetMinPinLength => {
+                let min_pin_length =
+                    min_pin_length.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                persistent_store.set_min_pin_length(min_pin_length)?;
+            }
+            ConfigSubCommand::VendorSetMaxMsgSize => {
+                let max_msg_size =
+                    max_msg_size.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                if max_msg_size < MIN_MSG_SIZE {
+                    return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+                }
+                persistent_store.set_max_msg_size(max_msg_size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the `maxMsgSize` `LargeBlobs` should fragment on: the value
+/// configured through `authenticatorConfig`'s vendor subcommand if one was
+/// set, or the compile-time default otherwise.
+pub fn max_msg_size(persistent_store: &PersistentStore) -> usize {
+    persistent_store.max_msg_size().unwrap_or(MAX_MSG_SIZE)
+}
+
+/// The `authenticatorConfig`-controlled entries of GetInfo's `options` map
+/// (CTAP 2.1 section 6.4): `enterpriseAttestation`, `alwaysUv`, and
+/// `minPINLength`.
+pub struct ConfigOptions {
+    pub enterprise_attestation: bool,
+    pub always_uv: bool,
+    pub min_pin_length: u8,
+}
+
+/// Reads the current `authenticatorConfig` settings out of `persistent_store`
+/// for GetInfo to report.
+pub fn get_info_options(persistent_store: &PersistentStore) -> ConfigOptions {
+    ConfigOptions {
+        enterprise_attestation: persistent_store.enterprise_attestation(),
+        always_uv: persistent_store.always_uv(),
+        min_pin_length: persistent_store.min_pin_length(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ctap::pin_protocol_v1::PinProtocolV1;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::ThreadRng256;
+    use crypto::sha256::Sha256;
+
+    /// Signs an authenticatorConfig subcommand the same way `process_command`
+    /// verifies it, so tests can exercise a subcommand's actual effect
+    /// instead of only its pin-auth gate.
+    fn sign_config_command(
+        pin_uv_auth_token: &[u8; 32],
+        sub_command: ConfigSubCommand,
+        sub_command_params: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x0D, sub_command as u8]);
+        if let Some(sub_command_params) = sub_command_params {
+            message.extend(sub_command_params);
+        }
+        hmac_256::<Sha256>(pin_uv_auth_token, &message)[..16].to_vec()
+    }
+
+    #[test]
+    fn test_default_max_msg_size_is_the_compile_time_constant() {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(max_msg_size(&persistent_store), MAX_MSG_SIZE);
+    }
+
+    #[test]
+    fn test_get_info_options_reflects_the_stored_settings() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+
+        let options = get_info_options(&persistent_store);
+        assert!(!options.enterprise_attestation);
+        assert!(!options.always_uv);
+        assert_eq!(options.min_pin_length, persistent_store.min_pin_length());
+
+        let pin_uv_auth_param = sign_config_command(
+            &pin_uv_auth_token,
+            ConfigSubCommand::EnableEnterpriseAttestation,
+            None,
+        );
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::EnableEnterpriseAttestation as u64,
+            sub_command_params: None,
+            min_pin_length: None,
+            max_msg_size: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        authenticator_config
+            .process_command(&mut persistent_store, &mut pin_protocol_v1, config_params)
+            .unwrap();
+
+        assert!(get_info_options(&persistent_store).enterprise_attestation);
+    }
+
+    #[test]
+    fn test_process_command_requires_pin_uv_auth_param() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::VendorSetMaxMsgSize as u64,
+            sub_command_params: None,
+            min_pin_length: None,
+            max_msg_size: Some(2048),
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: None,
+        };
+        assert_eq!(
+            authenticator_config.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                config_params,
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED),
+        );
+    }
+
+    #[test]
+    fn test_process_command_rejects_invalid_pin_uv_auth_param() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::VendorSetMaxMsgSize as u64,
+            sub_command_params: None,
+            min_pin_length: None,
+            max_msg_size: Some(2048),
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(vec![0x00; 16]),
+        };
+        assert_eq!(
+            authenticator_config.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                config_params,
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID),
+        );
+    }
+
+    #[test]
+    fn test_vendor_set_max_msg_size_updates_max_msg_size() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+
+        let pin_uv_auth_param = sign_config_command(
+            &pin_uv_auth_token,
+            ConfigSubCommand::VendorSetMaxMsgSize,
+            None,
+        );
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::VendorSetMaxMsgSize as u64,
+            sub_command_params: None,
+            min_pin_length: None,
+            max_msg_size: Some(2048),
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        assert_eq!(
+            authenticator_config.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                config_params,
+            ),
+            Ok(()),
+        );
+        assert_eq!(max_msg_size(&persistent_store), 2048);
+    }
+
+    #[test]
+    fn test_toggle_always_uv_flips_the_stored_setting() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+        assert!(!persistent_store.always_uv());
+
+        let pin_uv_auth_param =
+            sign_config_command(&pin_uv_auth_token, ConfigSubCommand::ToggleAlwaysUv, None);
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::ToggleAlwaysUv as u64,
+            sub_command_params: None,
+            min_pin_length: None,
+            max_msg_size: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        assert_eq!(
+            authenticator_config.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                config_params,
+            ),
+            Ok(()),
+        );
+        assert!(persistent_store.always_uv());
+    }
+
+    #[test]
+    fn test_set_min_pin_length_updates_the_stored_setting() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+
+        let pin_uv_auth_param =
+            sign_config_command(&pin_uv_auth_token, ConfigSubCommand::SetMinPinLength, None);
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::SetMinPinLength as u64,
+            sub_command_params: None,
+            min_pin_length: Some(6),
+            max_msg_size: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        assert_eq!(
+            authenticator_config.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                config_params,
+            ),
+            Ok(()),
+        );
+        assert_eq!(persistent_store.min_pin_length(), 6);
+    }
+
+    #[test]
+    fn test_enable_enterprise_attestation_updates_the_stored_setting() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut authenticator_config = AuthenticatorConfig::new();
+        assert!(!persistent_store.enterprise_attestation());
+
+        let pin_uv_auth_param = sign_config_command(
+            &pin_uv_auth_token,
+            ConfigSubCommand::EnableEnterpriseAttestation,
+            None,
+        );
+        let config_params = AuthenticatorConfigParameters {
+            sub_command: ConfigSubCommand::EnableEnterpriseAttestation as u64,
+            sub_command_params: None,
+            min_pin_length: None,
+            max_msg_size: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        assert_eq!(
+            authenticator_config.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                config_params,
+            ),
+            Ok(()),
+        );
+        assert!(persistent_store.enterprise_attestation());
+    }
+}
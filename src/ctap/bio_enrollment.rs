@@ -0,0 +1,650 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::check_pin_uv_auth_protocol;
+use super::pin_protocol::{PinPermission, PinUvAuthProtocol};
+use super::status_code::Ctap2StatusCode;
+use super::storage::PersistentStore;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The `authenticatorBioEnrollment` subcommands defined in CTAP 2.1 section
+/// 6.7. The capture loop subcommands (`enrollBegin`,
+/// `enrollCaptureNextSample`) do not take a `pinUvAuthParam`, since they are
+/// only reachable after `enrollBegin` itself has been authorized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BioEnrollmentSubCommand {
+    EnrollBegin = 0x01,
+    EnrollCaptureNextSample = 0x02,
+    CancelCurrentEnrollment = 0x03,
+    EnumerateEnrollments = 0x04,
+    SetFriendlyName = 0x05,
+    RemoveEnrollment = 0x06,
+    GetFingerprintSensorInfo = 0x07,
+}
+
+impl BioEnrollmentSubCommand {
+    fn from_int(value: u64) -> Result<BioEnrollmentSubCommand, Ctap2StatusCode> {
+        match value {
+            0x01 => Ok(BioEnrollmentSubCommand::EnrollBegin),
+            0x02 => Ok(BioEnrollmentSubCommand::EnrollCaptureNextSample),
+            0x03 => Ok(BioEnrollmentSubCommand::CancelCurrentEnrollment),
+            0x04 => Ok(BioEnrollmentSubCommand::EnumerateEnrollments),
+            0x05 => Ok(BioEnrollmentSubCommand::SetFriendlyName),
+            0x06 => Ok(BioEnrollmentSubCommand::RemoveEnrollment),
+            0x07 => Ok(BioEnrollmentSubCommand::GetFingerprintSensorInfo),
+            _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
+        }
+    }
+
+    /// `getFingerprintSensorInfo` is informational and, like the in-progress
+    /// capture subcommands, does not require a fresh `pinUvAuthParam`.
+    fn requires_pin_uv_auth_param(self) -> bool {
+        !matches!(
+            self,
+            BioEnrollmentSubCommand::GetFingerprintSensorInfo
+                | BioEnrollmentSubCommand::EnrollCaptureNextSample
+                | BioEnrollmentSubCommand::CancelCurrentEnrollment
+        )
+    }
+}
+
+/// Parameters of the `authenticatorBioEnrollment` command (CTAP 2.1 section
+/// 6.7).
+pub struct AuthenticatorBioEnrollmentParameters {
+    pub modality: Option<u64>,
+    pub sub_command: Option<u64>,
+    pub sub_command_params: Option<Vec<u8>>,
+    pub template_id: Option<Vec<u8>>,
+    pub template_friendly_name: Option<String>,
+    pub timeout_milliseconds: Option<u64>,
+    pub pin_uv_auth_protocol: Option<u64>,
+    pub pin_uv_auth_param: Option<Vec<u8>>,
+}
+
+/// The outcome of a single capture in the enrollment loop, surfaced to the
+/// platform so it can prompt the user for another sample or stop.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EnrollmentCaptureResponse {
+    pub template_id: Vec<u8>,
+    pub last_enroll_sample_status: u8,
+    pub remaining_samples: u8,
+}
+
+/// A single committed template as returned by `enumerateEnrollments`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BioTemplateInfo {
+    pub template_id: Vec<u8>,
+    pub template_friendly_name: Option<String>,
+}
+
+/// The fixed sensor characteristics returned by `getFingerprintSensorInfo`
+/// (CTAP 2.1 section 6.7.2).
+#[derive(Debug, PartialEq, Eq)]
+pub struct SensorInfo {
+    pub modality: u64,
+    pub fingerprint_kind: u64,
+    pub max_capture_samples_required_for_enroll: u8,
+    pub max_template_friendly_name: usize,
+}
+
+/// The three shapes of non-empty response `authenticatorBioEnrollment` can
+/// produce: a capture-loop update, the list of committed templates from
+/// `enumerateEnrollments`, or the sensor's fixed characteristics from
+/// `getFingerprintSensorInfo`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthenticatorBioEnrollmentResponse {
+    Capture(EnrollmentCaptureResponse),
+    Enumerate(Vec<BioTemplateInfo>),
+    SensorInfo(SensorInfo),
+}
+
+/// How many good samples a single enrollment needs before the template is
+/// considered complete.
+const SAMPLES_REQUIRED: u8 = 4;
+
+/// The fingerprint modality, the only one `BioEnrollment` supports (CTAP 2.1
+/// section 6.7.2, `modality`).
+const FINGERPRINT_MODALITY: u64 = 1;
+
+/// This authenticator's sensor is touch-based rather than swipe-based (CTAP
+/// 2.1 section 6.7.2, `fingerprintKind`: `1` is touch, `2` is swipe).
+const FINGERPRINT_KIND_TOUCH: u64 = 1;
+
+/// The longest `templateFriendlyName` `setFriendlyName` accepts.
+const MAX_TEMPLATE_FRIENDLY_NAME_LEN: usize = 32;
+
+/// Implements the logic for the `authenticatorBioEnrollment` command. Unlike
+/// `LargeBlobs`, the only state kept across calls is which enrollment (if
+/// any) is mid-capture; the completed templates themselves live in
+/// `PersistentStore`.
+pub struct BioEnrollment {
+    current_template_id: Option<Vec<u8>>,
+    samples_collected: u8,
+}
+
+impl BioEnrollment {
+    pub fn new() -> BioEnrollment {
+        BioEnrollment {
+            current_template_id: None,
+            samples_collected: 0,
+        }
+    }
+
+    /// Process the authenticatorBioEnrollment command.
+    pub fn process_command(
+        &mut self,
+        persistent_store: &mut PersistentStore,
+        pin_uv_auth_protocol: &mut dyn PinUvAuthProtocol,
+        bio_params: AuthenticatorBioEnrollmentParameters,
+    ) -> Result<Option<AuthenticatorBioEnrollmentResponse>, Ctap2StatusCode> {
+        let AuthenticatorBioEnrollmentParameters {
+            modality: _modality,
+            sub_command,
+            sub_command_params,
+            template_id,
+            template_friendly_name,
+            timeout_milliseconds: _timeout_milliseconds,
+            pin_uv_auth_protocol: pin_uv_auth_protocol_id,
+            pin_uv_auth_param,
+        } = bio_params;
+
+        let sub_command = match sub_command {
+            None => return Ok(None),
+            Some(sub_command) => BioEnrollmentSubCommand::from_int(sub_command)?,
+        };
+
+        if sub_command.requires_pin_uv_auth_param() {
+            let pin_uv_auth_param =
+                pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED)?;
+            check_pin_uv_auth_protocol(pin_uv_auth_protocol_id)?;
+            pin_uv_auth_protocol.has_permission(PinPermission::BioEnrollment)?;
+            let mut message = vec![0xFF; 32];
+            message.extend(&[0x09, sub_command as u8]);
+            // The subcommand's own parameters must be part of the signed
+            // message: otherwise a pinUvAuthParam captured for one
+            // template_id/friendly_name would verify for any other, since the
+            // message above is identical for every SetFriendlyName or
+            // RemoveEnrollment call. Fold in the raw, already CBOR-encoded
+            // params bytes (like AuthenticatorConfig does), not the decoded
+            // template_id/template_friendly_name fields: a real platform
+            // signs over the canonical CBOR map, which this ad hoc
+            // concatenation would never byte-match.
+            if let Some(sub_command_params) = sub_command_params.as_ref() {
+                message.extend(sub_command_params);
+            }
+            if !pin_uv_auth_protocol.verify_pin_auth_token(&message, &pin_uv_auth_param) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+        }
+
+        match sub_command {
+            BioEnrollmentSubCommand::GetFingerprintSensorInfo => Ok(Some(
+                AuthenticatorBioEnrollmentResponse::SensorInfo(SensorInfo {
+                    modality: FINGERPRINT_MODALITY,
+                    fingerprint_kind: FINGERPRINT_KIND_TOUCH,
+                    max_capture_samples_required_for_enroll: SAMPLES_REQUIRED,
+                    max_template_friendly_name: MAX_TEMPLATE_FRIENDLY_NAME_LEN,
+                }),
+            )),
+            BioEnrollmentSubCommand::EnrollBegin => {
+                // A fresh enrollBegin supersedes whatever capture loop was
+                // already in progress; release its uncommitted templateId
+                // before handing out a new one, or repeated abandoned
+                // enrollments would permanently burn down the id space.
+                if let Some(abandoned_template_id) = self.current_template_id.take() {
+                    persistent_store.release_bio_template(&abandoned_template_id);
+                }
+                let template_id = persistent_store.allocate_bio_template()?;
+                self.current_template_id = Some(template_id.clone());
+                self.samples_collected = 1;
+                Ok(Some(AuthenticatorBioEnrollmentResponse::Capture(
+                    EnrollmentCaptureResponse {
+                        template_id,
+                        last_enroll_sample_status: 0,
+                        remaining_samples: SAMPLES_REQUIRED - self.samples_collected,
+                    },
+                )))
+            }
+            BioEnrollmentSubCommand::EnrollCaptureNextSample => {
+                let template_id = self
+                    .current_template_id
+                    .clone()
+                    .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+                self.samples_collected += 1;
+                let remaining_samples = SAMPLES_REQUIRED.saturating_sub(self.samples_collected);
+                if remaining_samples == 0 {
+                    persistent_store.commit_bio_template(&template_id)?;
+                    self.current_template_id = None;
+                    self.samples_collected = 0;
+                }
+                Ok(Some(AuthenticatorBioEnrollmentResponse::Capture(
+                    EnrollmentCaptureResponse {
+                        template_id,
+                        last_enroll_sample_status: 0,
+                        remaining_samples,
+                    },
+                )))
+            }
+            BioEnrollmentSubCommand::CancelCurrentEnrollment => {
+                if let Some(template_id) = self.current_template_id.take() {
+                    persistent_store.release_bio_template(&template_id);
+                }
+                self.samples_collected = 0;
+                Ok(None)
+            }
+            BioEnrollmentSubCommand::EnumerateEnrollments => {
+                let templates = persistent_store
+                    .enumerate_bio_templates()?
+                    .into_iter()
+                    .map(|(template_id, template_friendly_name)| BioTemplateInfo {
+                        template_id,
+                        template_friendly_name,
+                    })
+                    .collect();
+                Ok(Some(AuthenticatorBioEnrollmentResponse::Enumerate(
+                    templates,
+                )))
+            }
+            BioEnrollmentSubCommand::SetFriendlyName => {
+                let template_id =
+                    template_id.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                let friendly_name =
+                    template_friendly_name.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                persistent_store.set_bio_template_friendly_name(&template_id, friendly_name)?;
+                Ok(None)
+            }
+            BioEnrollmentSubCommand::RemoveEnrollment => {
+                let template_id =
+                    template_id.ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                persistent_store.remove_bio_template(&template_id)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Whether to advertise `bioEnroll: true` in GetInfo's `options` map (CTAP
+/// 2.1 section 6.4). `BioEnrollment` is always compiled in, so this is
+/// always `true`.
+pub fn get_info_option() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ctap::pin_protocol_v1::PinProtocolV1;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::ThreadRng256;
+    use crypto::sha256::Sha256;
+
+    /// Signs a bio enrollment subcommand the same way `process_command`
+    /// verifies it, so tests can exercise the authenticated subcommands
+    /// instead of only their pin-auth gate.
+    fn sign_bio_command(
+        pin_uv_auth_token: &[u8; 32],
+        sub_command: BioEnrollmentSubCommand,
+        sub_command_params: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut message = vec![0xFF; 32];
+        message.extend(&[0x09, sub_command as u8]);
+        if let Some(sub_command_params) = sub_command_params {
+            message.extend(sub_command_params);
+        }
+        hmac_256::<Sha256>(pin_uv_auth_token, &message)[..16].to_vec()
+    }
+
+    #[test]
+    fn test_enroll_begin_requires_pin_uv_auth_param() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::EnrollBegin as u64),
+            sub_command_params: None,
+            template_id: None,
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: None,
+        };
+        assert_eq!(
+            bio_enrollment
+                .process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params)
+                .unwrap_err(),
+            Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED,
+        );
+    }
+
+    #[test]
+    fn test_capture_next_sample_without_enroll_begin_fails() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::EnrollCaptureNextSample as u64),
+            sub_command_params: None,
+            template_id: None,
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        assert_eq!(
+            bio_enrollment
+                .process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params)
+                .unwrap_err(),
+            Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED,
+        );
+    }
+
+    #[test]
+    fn test_get_fingerprint_sensor_info_does_not_require_pin_uv_auth_param() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::GetFingerprintSensorInfo as u64),
+            sub_command_params: None,
+            template_id: None,
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        assert_eq!(
+            bio_enrollment
+                .process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params)
+                .unwrap(),
+            Some(AuthenticatorBioEnrollmentResponse::SensorInfo(SensorInfo {
+                modality: FINGERPRINT_MODALITY,
+                fingerprint_kind: FINGERPRINT_KIND_TOUCH,
+                max_capture_samples_required_for_enroll: SAMPLES_REQUIRED,
+                max_template_friendly_name: MAX_TEMPLATE_FRIENDLY_NAME_LEN,
+            })),
+        );
+    }
+
+    #[test]
+    fn test_enroll_begin_and_capture_to_completion() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let pin_uv_auth_param = sign_bio_command(
+            &pin_uv_auth_token,
+            BioEnrollmentSubCommand::EnrollBegin,
+            None,
+        );
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::EnrollBegin as u64),
+            sub_command_params: None,
+            template_id: None,
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        let response = bio_enrollment
+            .process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params)
+            .unwrap()
+            .unwrap();
+        let template_id = match response {
+            AuthenticatorBioEnrollmentResponse::Capture(capture) => {
+                assert_eq!(capture.remaining_samples, SAMPLES_REQUIRED - 1);
+                capture.template_id
+            }
+            AuthenticatorBioEnrollmentResponse::Enumerate(_) => panic!("unexpected response"),
+            AuthenticatorBioEnrollmentResponse::SensorInfo(_) => panic!("unexpected response"),
+        };
+
+        // enrollCaptureNextSample doesn't carry a pinUvAuthParam.
+        for remaining in (0..SAMPLES_REQUIRED - 1).rev() {
+            let bio_params = AuthenticatorBioEnrollmentParameters {
+                modality: Some(1),
+                sub_command: Some(BioEnrollmentSubCommand::EnrollCaptureNextSample as u64),
+                sub_command_params: None,
+                template_id: None,
+                template_friendly_name: None,
+                timeout_milliseconds: None,
+                pin_uv_auth_protocol: None,
+                pin_uv_auth_param: None,
+            };
+            let response = bio_enrollment
+                .process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params)
+                .unwrap()
+                .unwrap();
+            match response {
+                AuthenticatorBioEnrollmentResponse::Capture(capture) => {
+                    assert_eq!(capture.template_id, template_id);
+                    assert_eq!(capture.remaining_samples, remaining);
+                }
+                AuthenticatorBioEnrollmentResponse::Enumerate(_) => panic!("unexpected response"),
+                AuthenticatorBioEnrollmentResponse::SensorInfo(_) => panic!("unexpected response"),
+            }
+        }
+
+        assert_eq!(
+            persistent_store.enumerate_bio_templates().unwrap(),
+            vec![(template_id, None)],
+        );
+    }
+
+    #[test]
+    fn test_enumerate_enrollments_returns_committed_templates() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let template_id = persistent_store.allocate_bio_template().unwrap();
+        persistent_store.commit_bio_template(&template_id).unwrap();
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let pin_uv_auth_param = sign_bio_command(
+            &pin_uv_auth_token,
+            BioEnrollmentSubCommand::EnumerateEnrollments,
+            None,
+        );
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::EnumerateEnrollments as u64),
+            sub_command_params: None,
+            template_id: None,
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        let response = bio_enrollment
+            .process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            response,
+            AuthenticatorBioEnrollmentResponse::Enumerate(vec![BioTemplateInfo {
+                template_id,
+                template_friendly_name: None,
+            }]),
+        );
+    }
+
+    #[test]
+    fn test_set_friendly_name_persists_the_name() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let template_id = persistent_store.allocate_bio_template().unwrap();
+        persistent_store.commit_bio_template(&template_id).unwrap();
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        // Stands in for the CBOR-encoded {templateId, templateFriendlyName}
+        // map a real platform would sign over; this trimmed tree has no CBOR
+        // parser to produce it from, so the bytes are just concatenated like
+        // `sign_config_command`'s callers do for `authenticatorConfig`.
+        let mut sub_command_params = template_id.clone();
+        sub_command_params.extend(b"left thumb");
+        let pin_uv_auth_param = sign_bio_command(
+            &pin_uv_auth_token,
+            BioEnrollmentSubCommand::SetFriendlyName,
+            Some(&sub_command_params),
+        );
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::SetFriendlyName as u64),
+            sub_command_params: Some(sub_command_params),
+            template_id: Some(template_id.clone()),
+            template_friendly_name: Some(String::from("left thumb")),
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        assert_eq!(
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params),
+            Ok(None),
+        );
+        assert_eq!(
+            persistent_store.enumerate_bio_templates().unwrap(),
+            vec![(template_id, Some(String::from("left thumb")))],
+        );
+    }
+
+    #[test]
+    fn test_remove_enrollment_removes_the_template() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let template_id = persistent_store.allocate_bio_template().unwrap();
+        persistent_store.commit_bio_template(&template_id).unwrap();
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let sub_command_params = template_id.clone();
+        let pin_uv_auth_param = sign_bio_command(
+            &pin_uv_auth_token,
+            BioEnrollmentSubCommand::RemoveEnrollment,
+            Some(&sub_command_params),
+        );
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::RemoveEnrollment as u64),
+            sub_command_params: Some(sub_command_params),
+            template_id: Some(template_id),
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: Some(1),
+            pin_uv_auth_param: Some(pin_uv_auth_param),
+        };
+        assert_eq!(
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params),
+            Ok(None),
+        );
+        assert_eq!(persistent_store.enumerate_bio_templates().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_abandoned_enroll_begin_does_not_burn_down_the_id_space() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut bio_enrollment = BioEnrollment::new();
+
+        let enroll_begin = |bio_enrollment: &mut BioEnrollment,
+                             persistent_store: &mut PersistentStore,
+                             pin_protocol_v1: &mut PinProtocolV1| {
+            let pin_uv_auth_param = sign_bio_command(
+                &pin_uv_auth_token,
+                BioEnrollmentSubCommand::EnrollBegin,
+                None,
+            );
+            let bio_params = AuthenticatorBioEnrollmentParameters {
+                modality: Some(1),
+                sub_command: Some(BioEnrollmentSubCommand::EnrollBegin as u64),
+                sub_command_params: None,
+                template_id: None,
+                template_friendly_name: None,
+                timeout_milliseconds: None,
+                pin_uv_auth_protocol: Some(1),
+                pin_uv_auth_param: Some(pin_uv_auth_param),
+            };
+            match bio_enrollment
+                .process_command(persistent_store, pin_protocol_v1, bio_params)
+                .unwrap()
+                .unwrap()
+            {
+                AuthenticatorBioEnrollmentResponse::Capture(capture) => capture.template_id,
+                AuthenticatorBioEnrollmentResponse::Enumerate(_) => panic!("unexpected response"),
+                AuthenticatorBioEnrollmentResponse::SensorInfo(_) => panic!("unexpected response"),
+            }
+        };
+
+        // Abandoning an enrollment (no capture, no cancel) by starting a new
+        // one must reclaim the old templateId, not burn through another slot
+        // of the u8 id space.
+        let abandoned_count = u8::MAX as usize + 1;
+        for _ in 0..abandoned_count {
+            enroll_begin(&mut bio_enrollment, &mut persistent_store, &mut pin_protocol_v1);
+        }
+
+        // An explicit cancel must reclaim the id the same way. Like
+        // enrollCaptureNextSample, cancelCurrentEnrollment carries no
+        // pinUvAuthParam of its own.
+        let template_id =
+            enroll_begin(&mut bio_enrollment, &mut persistent_store, &mut pin_protocol_v1);
+        let bio_params = AuthenticatorBioEnrollmentParameters {
+            modality: Some(1),
+            sub_command: Some(BioEnrollmentSubCommand::CancelCurrentEnrollment as u64),
+            sub_command_params: None,
+            template_id: None,
+            template_friendly_name: None,
+            timeout_milliseconds: None,
+            pin_uv_auth_protocol: None,
+            pin_uv_auth_param: None,
+        };
+        assert_eq!(
+            bio_enrollment.process_command(&mut persistent_store, &mut pin_protocol_v1, bio_params),
+            Ok(None),
+        );
+        assert_eq!(
+            enroll_begin(&mut bio_enrollment, &mut persistent_store, &mut pin_protocol_v1),
+            template_id,
+        );
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `largeBlobKey` extension's get-assertion side (CTAP 2.1 section
+//! 11.3). The full `authenticatorGetAssertion` command lives outside this
+//! trimmed tree; see `make_credential` for where the key returned here was
+//! generated and stored.
+
+use super::large_blob_key::LARGE_BLOB_KEY_LEN;
+use super::storage::PersistentStore;
+
+/// Looks up the `largeBlobKey` stored for `credential_id`, if the extension
+/// was requested on this assertion and a key was stored for that credential
+/// at creation time.
+pub fn process_large_blob_key_extension(
+    persistent_store: &PersistentStore,
+    credential_id: &[u8],
+    large_blob_key_requested: bool,
+) -> Option<[u8; LARGE_BLOB_KEY_LEN]> {
+    if !large_blob_key_requested {
+        return None;
+    }
+    persistent_store.large_blob_key(credential_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_process_large_blob_key_extension_not_requested() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_id = vec![0x01; 16];
+        persistent_store.store_large_blob_key(&credential_id, [0x55; LARGE_BLOB_KEY_LEN]);
+        assert_eq!(
+            process_large_blob_key_extension(&persistent_store, &credential_id, false),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_process_large_blob_key_extension_round_trips_with_make_credential() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_id = vec![0x01; 16];
+        let stored_key = super::super::make_credential::process_large_blob_key_extension(
+            &mut persistent_store,
+            &mut rng,
+            &credential_id,
+            true,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            process_large_blob_key_extension(&persistent_store, &credential_id, true),
+            Some(stored_key),
+        );
+    }
+
+    #[test]
+    fn test_process_large_blob_key_extension_unknown_credential() {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        let credential_id = vec![0x01; 16];
+        assert_eq!(
+            process_large_blob_key_extension(&persistent_store, &credential_id, true),
+            None,
+        );
+    }
+}
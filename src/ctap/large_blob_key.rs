@@ -0,0 +1,68 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-credential key management for the `largeBlobKey` extension.
+//!
+//! The authenticator never reads or writes the large-blob array's contents on
+//! a credential's behalf (see `large_blobs` for that array's own commit/get
+//! protocol and hash). It only generates and custodies a 32-byte key per
+//! discoverable credential, handing it back to the platform on creation (from
+//! `make_credential`) and on assertion (from `get_assertion`), alongside the
+//! storage glue that keeps that key next to the credential in
+//! `PersistentStore`.
+//!
+//! The platform, not the authenticator, uses the key to decrypt its entry out
+//! of the committed large-blob array. That entry is a CBOR array of maps, one
+//! per credential, each shaped as:
+//!   - `1`: ciphertext (bstr) - the blob, encrypted with AES-256-GCM under the
+//!     per-credential key.
+//!   - `2`: nonce (bstr, 12 bytes) - the AES-256-GCM nonce used above.
+//!   - `3`: origSize (uint) - the plaintext length, to recover padding.
+
+use crypto::rng256::Rng256;
+
+/// Length in bytes of the `largeBlobKey` returned to the platform.
+pub const LARGE_BLOB_KEY_LEN: usize = 32;
+
+/// Generates a fresh, random per-credential key for the `largeBlobKey`
+/// extension.
+///
+/// Called by `make_credential` when the request carries
+/// `"largeBlobKey": true` for a discoverable credential. The returned key is
+/// persisted next to the credential and returned unchanged in the
+/// authenticator response.
+pub fn new_large_blob_key(rng: &mut impl Rng256) -> [u8; LARGE_BLOB_KEY_LEN] {
+    rng.gen_uniform_u8x32()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_new_large_blob_key_is_full_length() {
+        let mut rng = ThreadRng256 {};
+        let key = new_large_blob_key(&mut rng);
+        assert_eq!(key.len(), LARGE_BLOB_KEY_LEN);
+    }
+
+    #[test]
+    fn test_new_large_blob_key_is_random() {
+        let mut rng = ThreadRng256 {};
+        let key_one = new_large_blob_key(&mut rng);
+        let key_two = new_large_blob_key(&mut rng);
+        assert_ne!(key_one, key_two);
+    }
+}
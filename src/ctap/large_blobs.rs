@@ -14,7 +14,7 @@
 
 use super::check_pin_uv_auth_protocol;
 use super::command::AuthenticatorLargeBlobsParameters;
-use super::pin_protocol_v1::{PinPermission, PinProtocolV1};
+use super::pin_protocol::{PinPermission, PinUvAuthProtocol};
 use super::response::{AuthenticatorLargeBlobsResponse, ResponseData};
 use super::status_code::Ctap2StatusCode;
 use super::storage::PersistentStore;
@@ -28,11 +28,33 @@ use crypto::Hash256;
 /// Increasing this values can speed up commands with longer responses, but lead to
 /// packets dropping or unexpected failures.
 pub const MAX_MSG_SIZE: usize = 1024;
+/// The smallest `maxMsgSize` authenticatorConfig's vendor subcommand accepts.
+/// CTAP 2.1 requires authenticators support at least this message size, and
+/// `max_fragment_length` below subtracts a fixed 64-byte overhead from
+/// whatever is configured, so anything smaller would underflow or leave no
+/// room to fragment large blobs at all.
+pub const MIN_MSG_SIZE: usize = 1024;
 /// The length of the truncated hash that as appended to the large blob data.
 const TRUNCATED_HASH_LEN: usize = 16;
 
+/// Both pin/uv auth protocols (see `pin_protocol`) are accepted here: the
+/// caller passes in whichever `PinUvAuthProtocol` implementation matches the
+/// `pinUvAuthProtocol` the platform negotiated, and GetInfo's
+/// `pinUvAuthProtocols` lists `1` and `2` accordingly.
+///
+/// See `large_blob_key` for the `largeBlobKey` extension and the CBOR layout
+/// of the entries that extension's keys decrypt out of the array committed
+/// here.
+pub use super::large_blob_key::LARGE_BLOB_KEY_LEN;
+
 pub struct LargeBlobs {
-    buffer: Vec<u8>,
+    // Bytes received for the current offset that cannot be hashed or written
+    // to storage yet, because they might still turn out to be (a prefix of)
+    // the trailing truncated hash. Never grows beyond a fragment's length
+    // plus TRUNCATED_HASH_LEN, unlike buffering the whole large-blob array.
+    pending: Vec<u8>,
+    // Running hash of everything written so far, excluding `pending`.
+    hasher: Sha256,
     expected_length: usize,
     expected_next_offset: usize,
 }
@@ -41,17 +63,24 @@ pub struct LargeBlobs {
 impl LargeBlobs {
     pub fn new() -> LargeBlobs {
         LargeBlobs {
-            buffer: Vec::new(),
+            pending: Vec::new(),
+            hasher: Sha256::new(),
             expected_length: 0,
             expected_next_offset: 0,
         }
     }
 
     /// Process the large blob command.
+    ///
+    /// `pin_uv_auth_protocol` is whichever pin/uv auth protocol implementation
+    /// the caller already selected based on the client's negotiated
+    /// `pinUvAuthProtocol` (1 or 2); this command only needs to verify a
+    /// `pinUvAuthParam` and check permissions through that protocol, never
+    /// which version it is.
     pub fn process_command(
         &mut self,
         persistent_store: &mut PersistentStore,
-        pin_protocol_v1: &mut PinProtocolV1,
+        pin_uv_auth_protocol: &mut dyn PinUvAuthProtocol,
         large_blobs_params: AuthenticatorLargeBlobsParameters,
     ) -> Result<ResponseData, Ctap2StatusCode> {
         let AuthenticatorLargeBlobsParameters {
@@ -60,13 +89,15 @@ impl LargeBlobs {
             offset,
             length,
             pin_uv_auth_param,
-            pin_uv_auth_protocol,
+            pin_uv_auth_protocol: pin_uv_auth_protocol_id,
         } = large_blobs_params;
 
-        const MAX_FRAGMENT_LENGTH: usize = MAX_MSG_SIZE - 64;
+        // authenticatorConfig's vendor subcommand can raise this above the
+        // MAX_MSG_SIZE default at runtime; see authenticator_config::max_msg_size.
+        let max_fragment_length = super::authenticator_config::max_msg_size(persistent_store) - 64;
 
         if let Some(get) = get {
-            if get > MAX_FRAGMENT_LENGTH {
+            if get > max_fragment_length {
                 return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
             }
             let config = persistent_store.get_large_blob_array(get, offset)?;
@@ -76,7 +107,7 @@ impl LargeBlobs {
         }
 
         if let Some(mut set) = set {
-            if set.len() > MAX_FRAGMENT_LENGTH {
+            if set.len() > max_fragment_length {
                 return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH);
             }
             if offset == 0 {
@@ -93,50 +124,101 @@ impl LargeBlobs {
                     pin_uv_auth_param.ok_or(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED)?;
                 // TODO(kaczmarczyck) Error codes for PIN protocol differ across commands.
                 // Change to Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED for None?
-                check_pin_uv_auth_protocol(pin_uv_auth_protocol)?;
-                pin_protocol_v1.has_permission(PinPermission::LargeBlobWrite)?;
+                check_pin_uv_auth_protocol(pin_uv_auth_protocol_id)?;
+                pin_uv_auth_protocol.has_permission(PinPermission::LargeBlobWrite)?;
                 let mut message = vec![0xFF; 32];
                 message.extend(&[0x0C, 0x00]);
                 let mut offset_bytes = [0u8; 4];
                 LittleEndian::write_u32(&mut offset_bytes, offset as u32);
                 message.extend(&offset_bytes);
                 message.extend(&Sha256::hash(set.as_slice()));
-                if !pin_protocol_v1.verify_pin_auth_token(&message, &pin_uv_auth_param) {
+                if !pin_uv_auth_protocol.verify_pin_auth_token(&message, &pin_uv_auth_param) {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
                 }
             }
             if offset + set.len() > self.expected_length {
                 return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
             }
+            let fragment_len = set.len();
+            let is_last_fragment = offset + fragment_len == self.expected_length;
             if offset == 0 {
-                self.buffer = Vec::with_capacity(self.expected_length);
+                self.pending = Vec::new();
+                self.hasher = Sha256::new();
+                persistent_store.start_large_blob_array_write(self.expected_length)?;
             }
-            self.buffer.append(&mut set);
-            self.expected_next_offset = self.buffer.len();
-            if self.expected_next_offset == self.expected_length {
-                self.expected_length = 0;
-                self.expected_next_offset = 0;
-                // Must be a positive number.
-                let buffer_hash_index = self.buffer.len() - TRUNCATED_HASH_LEN;
-                if Sha256::hash(&self.buffer[..buffer_hash_index])[..TRUNCATED_HASH_LEN]
-                    != self.buffer[buffer_hash_index..]
-                {
-                    self.buffer = Vec::new();
-                    return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+            self.pending.append(&mut set);
+            self.expected_next_offset = offset + fragment_len;
+
+            // Only bytes that cannot still turn out to be part of the trailing
+            // hash are fed to the running digest and written through to
+            // storage; this keeps memory use bounded by a fragment's size
+            // (plus TRUNCATED_HASH_LEN) rather than the whole large-blob array.
+            let hashable_len = self.pending.len().saturating_sub(TRUNCATED_HASH_LEN);
+            let hashable: Vec<u8> = self.pending.drain(..hashable_len).collect();
+            if !hashable.is_empty() {
+                self.hasher.update(&hashable);
+                if let Err(error) = persistent_store.write_large_blob_array_chunk(&hashable) {
+                    persistent_store.abort_large_blob_array_write()?;
+                    self.reset();
+                    return Err(error);
                 }
-                persistent_store.commit_large_blob_array(&self.buffer)?;
-                self.buffer = Vec::new();
             }
+
+            if !is_last_fragment {
+                return Ok(ResponseData::AuthenticatorLargeBlobs(None));
+            }
+
+            if self.pending.len() != TRUNCATED_HASH_LEN {
+                // Only reachable if expected_length was smaller than
+                // TRUNCATED_HASH_LEN, which the command layer already rejects.
+                persistent_store.abort_large_blob_array_write()?;
+                self.reset();
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            let trailing_hash = core::mem::take(&mut self.pending);
+            let digest = core::mem::replace(&mut self.hasher, Sha256::new()).finalize();
+            self.reset();
+            if digest[..TRUNCATED_HASH_LEN] != trailing_hash[..] {
+                persistent_store.abort_large_blob_array_write()?;
+                return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+            }
+            // The trailing hash itself is part of the committed array (`get`
+            // returns it back verbatim), so it still has to reach storage even
+            // though it was held back from `hashable` above.
+            if let Err(error) = persistent_store.write_large_blob_array_chunk(&trailing_hash) {
+                persistent_store.abort_large_blob_array_write()?;
+                return Err(error);
+            }
+            persistent_store.commit_large_blob_array_write()?;
             return Ok(ResponseData::AuthenticatorLargeBlobs(None));
         }
 
         // This should be unreachable, since the command has either get or set.
         Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
     }
+
+    /// Clears the in-progress set state, so the next fragment with offset 0
+    /// starts a fresh large-blob array.
+    fn reset(&mut self) {
+        self.pending = Vec::new();
+        self.hasher = Sha256::new();
+        self.expected_length = 0;
+        self.expected_next_offset = 0;
+    }
+}
+
+/// Whether to advertise `largeBlobs: true` in GetInfo's `options` map (CTAP
+/// 2.1 section 6.4). `LargeBlobs` is always compiled in, so this is always
+/// `true`; it exists as a function, rather than a bare literal inlined into
+/// GetInfo's response, so every option source response.rs reads from looks
+/// the same.
+pub fn get_info_option() -> bool {
+    true
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::pin_protocol_v1::PinProtocolV1;
     use super::*;
     use crypto::rng256::ThreadRng256;
 
@@ -419,4 +501,127 @@ mod test {
             Ok(ResponseData::AuthenticatorLargeBlobs(None))
         );
     }
+
+    #[test]
+    fn test_process_command_commit_many_small_fragments() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut large_blobs = LargeBlobs::new();
+
+        // Every fragment is far smaller than TRUNCATED_HASH_LEN, so the
+        // trailing hash always arrives split across several fragments. A
+        // naive rolling buffer of exactly TRUNCATED_HASH_LEN bytes would fail
+        // to hold it all before the final fragment confirms where it starts.
+        const FRAGMENT_LEN: usize = 3;
+        const DATA_LEN: usize = 500;
+        const BLOB_LEN: usize = DATA_LEN + TRUNCATED_HASH_LEN;
+        let mut large_blob = vec![0x42; DATA_LEN];
+        large_blob.extend_from_slice(&Sha256::hash(&large_blob[..])[..TRUNCATED_HASH_LEN]);
+
+        let mut offset = 0;
+        while offset < BLOB_LEN {
+            let end = core::cmp::min(offset + FRAGMENT_LEN, BLOB_LEN);
+            let large_blobs_params = AuthenticatorLargeBlobsParameters {
+                get: None,
+                set: Some(large_blob[offset..end].to_vec()),
+                offset,
+                length: if offset == 0 { Some(BLOB_LEN) } else { None },
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            };
+            let large_blobs_response = large_blobs.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                large_blobs_params,
+            );
+            assert_eq!(
+                large_blobs_response,
+                Ok(ResponseData::AuthenticatorLargeBlobs(None))
+            );
+            offset = end;
+        }
+
+        let large_blobs_params = AuthenticatorLargeBlobsParameters {
+            get: Some(BLOB_LEN),
+            set: None,
+            offset: 0,
+            length: None,
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let large_blobs_response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            large_blobs_params,
+        );
+        match large_blobs_response.unwrap() {
+            ResponseData::AuthenticatorLargeBlobs(Some(response)) => {
+                assert_eq!(response.config, large_blob);
+            }
+            _ => panic!("Invalid response type"),
+        };
+    }
+
+    #[test]
+    fn test_process_command_commit_many_small_fragments_bad_hash() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let mut pin_protocol_v1 = PinProtocolV1::new_test(key_agreement_key, pin_uv_auth_token);
+        let mut large_blobs = LargeBlobs::new();
+
+        const FRAGMENT_LEN: usize = 3;
+        const DATA_LEN: usize = 500;
+        const BLOB_LEN: usize = DATA_LEN + TRUNCATED_HASH_LEN;
+        // The trailing bytes do not match the hash of the data above them.
+        let large_blob = vec![0x42; BLOB_LEN];
+
+        let mut offset = 0;
+        let mut last_response = Ok(ResponseData::AuthenticatorLargeBlobs(None));
+        while offset < BLOB_LEN {
+            let end = core::cmp::min(offset + FRAGMENT_LEN, BLOB_LEN);
+            let large_blobs_params = AuthenticatorLargeBlobsParameters {
+                get: None,
+                set: Some(large_blob[offset..end].to_vec()),
+                offset,
+                length: if offset == 0 { Some(BLOB_LEN) } else { None },
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            };
+            last_response = large_blobs.process_command(
+                &mut persistent_store,
+                &mut pin_protocol_v1,
+                large_blobs_params,
+            );
+            offset = end;
+        }
+        assert_eq!(
+            last_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE),
+        );
+
+        // The failed commit must not have left behind a partial array: a
+        // fresh transfer starting at offset 0 is accepted normally.
+        let large_blobs_params = AuthenticatorLargeBlobsParameters {
+            get: None,
+            set: Some(large_blob[..FRAGMENT_LEN].to_vec()),
+            offset: 0,
+            length: Some(BLOB_LEN),
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let large_blobs_response = large_blobs.process_command(
+            &mut persistent_store,
+            &mut pin_protocol_v1,
+            large_blobs_params,
+        );
+        assert_eq!(
+            large_blobs_response,
+            Ok(ResponseData::AuthenticatorLargeBlobs(None))
+        );
+    }
 }
@@ -0,0 +1,107 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `largeBlobKey` extension's make-credential side (CTAP 2.1 section
+//! 11.3). The full `authenticatorMakeCredential` command (CBOR parsing,
+//! attestation, credential storage) lives outside this trimmed tree; this is
+//! the extension-specific slice of it that `PersistentStore` and
+//! `large_blob_key` need.
+
+use super::large_blob_key::{new_large_blob_key, LARGE_BLOB_KEY_LEN};
+use super::storage::PersistentStore;
+use crypto::rng256::Rng256;
+
+/// Generates and persists a fresh `largeBlobKey` for a newly created
+/// credential, if the extension was requested for it.
+///
+/// Per CTAP 2.1 section 11.3, the extension only applies to discoverable
+/// credentials; a non-discoverable credential gets no key, even if
+/// `"largeBlobKey": true` was requested, since the platform would have
+/// nowhere durable to look it up from on a later assertion.
+pub fn process_large_blob_key_extension(
+    persistent_store: &mut PersistentStore,
+    rng: &mut impl Rng256,
+    credential_id: &[u8],
+    large_blob_key_requested: bool,
+    is_discoverable: bool,
+) -> Option<[u8; LARGE_BLOB_KEY_LEN]> {
+    if !large_blob_key_requested || !is_discoverable {
+        return None;
+    }
+    let large_blob_key = new_large_blob_key(rng);
+    persistent_store.store_large_blob_key(credential_id, large_blob_key);
+    Some(large_blob_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_process_large_blob_key_extension_not_requested() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_id = vec![0x01; 16];
+        assert_eq!(
+            process_large_blob_key_extension(
+                &mut persistent_store,
+                &mut rng,
+                &credential_id,
+                false,
+                true,
+            ),
+            None,
+        );
+        assert_eq!(persistent_store.large_blob_key(&credential_id), None);
+    }
+
+    #[test]
+    fn test_process_large_blob_key_extension_non_discoverable() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_id = vec![0x01; 16];
+        assert_eq!(
+            process_large_blob_key_extension(
+                &mut persistent_store,
+                &mut rng,
+                &credential_id,
+                true,
+                false,
+            ),
+            None,
+        );
+        assert_eq!(persistent_store.large_blob_key(&credential_id), None);
+    }
+
+    #[test]
+    fn test_process_large_blob_key_extension_stores_the_returned_key() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        let credential_id = vec![0x01; 16];
+        let large_blob_key = process_large_blob_key_extension(
+            &mut persistent_store,
+            &mut rng,
+            &credential_id,
+            true,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            persistent_store.large_blob_key(&credential_id),
+            Some(large_blob_key),
+        );
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::status_code::Ctap2StatusCode;
+use alloc::vec::Vec;
+use crypto::rng256::Rng256;
+
+/// The `pinUvAuthProtocol` identifiers defined in CTAP 2.1 section 6.5.8.
+pub const PIN_PROTOCOL_VERSION_1: u64 = 1;
+pub const PIN_PROTOCOL_VERSION_2: u64 = 2;
+
+/// The `pinUvAuthProtocols` GetInfo advertises (CTAP 2.1 section 6.4), in
+/// the order the authenticator prefers the platform negotiate them.
+pub const SUPPORTED_PIN_UV_AUTH_PROTOCOLS: [u64; 2] =
+    [PIN_PROTOCOL_VERSION_1, PIN_PROTOCOL_VERSION_2];
+
+/// The command-level permissions a `pinUvAuthToken` can carry, as requested
+/// through `getPinUvAuthTokenUsingPinWithPermissions`/`...UsingUvWithPermissions`.
+/// The discriminants match the permissions bits from the CTAP 2.1 spec table
+/// (section 6.5.5.7), so they double as the bit to test in `has_permission`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinPermission {
+    MakeCredential = 0x01,
+    GetAssertion = 0x02,
+    CredentialManagement = 0x04,
+    BioEnrollment = 0x08,
+    LargeBlobWrite = 0x10,
+    AuthenticatorConfiguration = 0x20,
+}
+
+/// Abstracts the pin/uv auth protocol version (CTAP 2.1 section 6.5.8) behind
+/// a single interface, so that commands like `LargeBlobs` can verify and use
+/// a `pinUvAuthToken` without caring whether the platform negotiated protocol
+/// 1 or 2.
+pub trait PinUvAuthProtocol {
+    /// Encrypts `plaintext` with the protocol's shared secret. `rng` supplies
+    /// randomness for protocols (e.g. protocol 2) that need a fresh IV per
+    /// call; protocols without that need simply ignore it.
+    fn encrypt(&self, rng: &mut dyn Rng256, plaintext: &[u8]) -> Vec<u8>;
+
+    /// Decrypts `ciphertext` previously produced by `encrypt`.
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>>;
+
+    /// Verifies a `pinUvAuthParam` authenticating `message` under the current
+    /// `pinUvAuthToken`.
+    fn verify_pin_auth_token(&self, message: &[u8], pin_uv_auth_param: &[u8]) -> bool;
+
+    /// Bitfield of permissions the current `pinUvAuthToken` was granted,
+    /// built by OR-ing together `PinPermission` discriminants.
+    fn granted_permissions(&self) -> u8;
+
+    /// Checks that the current `pinUvAuthToken` was granted `permission`.
+    /// Implemented once here so every `PinUvAuthProtocol` checks permissions
+    /// the same way; implementers only need to track `granted_permissions`.
+    fn has_permission(&self, permission: PinPermission) -> Result<(), Ctap2StatusCode> {
+        if self.granted_permissions() & permission as u8 == 0 {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::pin_protocol::PinUvAuthProtocol;
+use alloc::vec::Vec;
+use crypto::cbc::{cbc_decrypt, cbc_encrypt};
+use crypto::hmac::verify_hmac_256;
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+
+/// Length in bytes of a `pinUvAuthToken`.
+const PIN_TOKEN_LENGTH: usize = 32;
+/// PIN protocol 1 truncates the HMAC-SHA256 tag to the first 16 bytes.
+const PIN_AUTH_LENGTH: usize = 16;
+
+/// Implements pin/uv auth protocol one (CTAP 2.1 section 6.5.8). The shared
+/// secret is the raw SHA-256 of the ECDH point's x-coordinate, used directly
+/// as both the AES-256-CBC key (with a zero IV) and the HMAC-SHA256 key.
+pub struct PinProtocolV1 {
+    key_agreement_key: crypto::ecdh::SecKey,
+    pin_uv_auth_token: [u8; PIN_TOKEN_LENGTH],
+    permissions: u8,
+}
+
+impl PinProtocolV1 {
+    pub fn new(
+        key_agreement_key: crypto::ecdh::SecKey,
+        pin_uv_auth_token: [u8; PIN_TOKEN_LENGTH],
+    ) -> PinProtocolV1 {
+        PinProtocolV1 {
+            key_agreement_key,
+            pin_uv_auth_token,
+            permissions: 0,
+        }
+    }
+
+    /// Builds a protocol instance whose token already carries every
+    /// permission, for use in tests that don't exercise permission checks.
+    #[cfg(test)]
+    pub fn new_test(
+        key_agreement_key: crypto::ecdh::SecKey,
+        pin_uv_auth_token: [u8; PIN_TOKEN_LENGTH],
+    ) -> PinProtocolV1 {
+        PinProtocolV1 {
+            key_agreement_key,
+            pin_uv_auth_token,
+            permissions: 0xFF,
+        }
+    }
+
+    pub fn set_permissions(&mut self, permissions: u8) {
+        self.permissions = permissions;
+    }
+
+    fn shared_secret(&self) -> [u8; 32] {
+        self.key_agreement_key.shared_secret_point_x()
+    }
+}
+
+impl PinUvAuthProtocol for PinProtocolV1 {
+    fn encrypt(&self, _rng: &mut dyn Rng256, plaintext: &[u8]) -> Vec<u8> {
+        cbc_encrypt(&self.shared_secret(), [0u8; 16], plaintext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        cbc_decrypt(&self.shared_secret(), [0u8; 16], ciphertext)
+    }
+
+    fn verify_pin_auth_token(&self, message: &[u8], pin_uv_auth_param: &[u8]) -> bool {
+        if pin_uv_auth_param.len() != PIN_AUTH_LENGTH {
+            return false;
+        }
+        verify_hmac_256::<Sha256>(&self.pin_uv_auth_token, message, pin_uv_auth_param)
+    }
+
+    fn granted_permissions(&self) -> u8 {
+        self.permissions
+    }
+}
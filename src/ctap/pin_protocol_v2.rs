@@ -0,0 +1,147 @@
+// Copyright 2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::pin_protocol::PinUvAuthProtocol;
+use alloc::vec::Vec;
+use crypto::cbc::{cbc_decrypt, cbc_encrypt};
+use crypto::hkdf::hkdf_256;
+use crypto::hmac::verify_hmac_256;
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+
+const AES_KEY_INFO: &[u8] = b"CTAP2 AES key";
+/// Unlike protocol 1, protocol 2 uses a random IV per encryption, prepended
+/// to the ciphertext.
+const IV_LENGTH: usize = 16;
+/// Protocol 2 uses the full HMAC-SHA256 tag, unlike protocol 1's 16 bytes.
+const PIN_AUTH_LENGTH: usize = 32;
+
+/// Implements pin/uv auth protocol two (CTAP 2.1 section 6.5.8). The ECDH
+/// shared secret is expanded with HKDF-SHA256 (zero salt) into the AES key
+/// `encrypt`/`decrypt` use, with a random IV prepended per encryption rather
+/// than protocol 1's fixed one. `verify_pin_auth_token`, like protocol 1's,
+/// authenticates directly against the `pinUvAuthToken` itself, not anything
+/// derived from the shared secret.
+pub struct PinProtocolV2 {
+    aes_key: [u8; 32],
+    pin_uv_auth_token: [u8; 32],
+    permissions: u8,
+}
+
+impl PinProtocolV2 {
+    pub fn new(
+        key_agreement_key: crypto::ecdh::SecKey,
+        pin_uv_auth_token: [u8; 32],
+    ) -> PinProtocolV2 {
+        let shared_secret = key_agreement_key.shared_secret_point_x();
+        let salt = [0u8; 32];
+        let aes_key = hkdf_256::<Sha256>(&salt, &shared_secret, AES_KEY_INFO);
+        PinProtocolV2 {
+            aes_key,
+            pin_uv_auth_token,
+            permissions: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new_test(
+        key_agreement_key: crypto::ecdh::SecKey,
+        pin_uv_auth_token: [u8; 32],
+    ) -> PinProtocolV2 {
+        let mut protocol = PinProtocolV2::new(key_agreement_key, pin_uv_auth_token);
+        protocol.permissions = 0xFF;
+        protocol
+    }
+
+    pub fn set_permissions(&mut self, permissions: u8) {
+        self.permissions = permissions;
+    }
+}
+
+impl PinUvAuthProtocol for PinProtocolV2 {
+    fn encrypt(&self, rng: &mut dyn Rng256, plaintext: &[u8]) -> Vec<u8> {
+        let iv: [u8; IV_LENGTH] = rng.gen_uniform_u8x32()[..IV_LENGTH].try_into().unwrap();
+        let mut ciphertext = iv.to_vec();
+        ciphertext.extend(cbc_encrypt(&self.aes_key, iv, plaintext));
+        ciphertext
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < IV_LENGTH {
+            return None;
+        }
+        let iv: [u8; IV_LENGTH] = ciphertext[..IV_LENGTH].try_into().ok()?;
+        cbc_decrypt(&self.aes_key, iv, &ciphertext[IV_LENGTH..])
+    }
+
+    fn verify_pin_auth_token(&self, message: &[u8], pin_uv_auth_param: &[u8]) -> bool {
+        if pin_uv_auth_param.len() != PIN_AUTH_LENGTH {
+            return false;
+        }
+        verify_hmac_256::<Sha256>(&self.pin_uv_auth_token, message, pin_uv_auth_param)
+    }
+
+    fn granted_permissions(&self) -> u8 {
+        self.permissions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::hmac::hmac_256;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_verify_pin_auth_token_accepts_a_token_signed_message() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let protocol = PinProtocolV2::new_test(key_agreement_key, pin_uv_auth_token);
+        let message = b"authenticatorClientPIN message";
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, message);
+        assert!(protocol.verify_pin_auth_token(message, &pin_uv_auth_param));
+    }
+
+    #[test]
+    fn test_verify_pin_auth_token_rejects_a_tampered_message() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let protocol = PinProtocolV2::new_test(key_agreement_key, pin_uv_auth_token);
+        let pin_uv_auth_param = hmac_256::<Sha256>(&pin_uv_auth_token, b"original message");
+        assert!(!protocol.verify_pin_auth_token(b"tampered message", &pin_uv_auth_param));
+    }
+
+    #[test]
+    fn test_verify_pin_auth_token_rejects_the_wrong_token() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let pin_uv_auth_token = [0x55; 32];
+        let protocol = PinProtocolV2::new_test(key_agreement_key, pin_uv_auth_token);
+        let message = b"authenticatorClientPIN message";
+        let pin_uv_auth_param = hmac_256::<Sha256>(&[0xAA; 32], message);
+        assert!(!protocol.verify_pin_auth_token(message, &pin_uv_auth_param));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_round_trip() {
+        let mut rng = ThreadRng256 {};
+        let key_agreement_key = crypto::ecdh::SecKey::gensk(&mut rng);
+        let protocol = PinProtocolV2::new_test(key_agreement_key, [0x55; 32]);
+        let plaintext = b"a secret pin".to_vec();
+        let ciphertext = protocol.encrypt(&mut rng, &plaintext);
+        assert_eq!(protocol.decrypt(&ciphertext), Some(plaintext));
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright 2019-2021 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::large_blob_key::LARGE_BLOB_KEY_LEN;
+use super::status_code::Ctap2StatusCode;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crypto::rng256::Rng256;
+use crypto::sha256::Sha256;
+use crypto::Hash256;
+
+/// The length of the truncated hash appended to a committed large-blob array;
+/// mirrors `large_blobs::TRUNCATED_HASH_LEN`; see CTAP 2.1 section 6.10.
+const TRUNCATED_HASH_LEN: usize = 16;
+
+/// The `minPINLength` a factory-reset authenticator reports, per CTAP 2.1
+/// section 6.11.3's `setMinPINLength` default.
+const DEFAULT_MIN_PIN_LENGTH: u8 = 4;
+
+/// The large-blob array committed by `LargeBlobs::process_command` while a
+/// `set` sequence is in progress. Kept separate from the committed array so a
+/// failed or aborted write never disturbs what `get` returns.
+struct LargeBlobArrayWrite {
+    buffer: Vec<u8>,
+}
+
+/// A stand-in for the flash-backed persistent storage the real authenticator
+/// uses. State lives only in memory, so it does not survive a restart, but
+/// every accessor here behaves like its flash-backed counterpart would:
+/// fallible, and consistent across the whole command surface that touches it.
+pub struct PersistentStore {
+    pin_hash: Option<[u8; 16]>,
+    pin_retries: u8,
+    large_blob_array: Vec<u8>,
+    large_blob_array_write: Option<LargeBlobArrayWrite>,
+    max_msg_size: Option<usize>,
+    enterprise_attestation: bool,
+    always_uv: bool,
+    min_pin_length: u8,
+    // Committed fingerprint templates, keyed by templateId. A template
+    // allocated through `allocate_bio_template` only lands here once
+    // `commit_bio_template` confirms the enrollment's capture loop finished.
+    bio_templates: BTreeMap<Vec<u8>, Option<String>>,
+    next_bio_template_id: u8,
+    // The largeBlobKey extension's per-credential keys, keyed by credential
+    // id. See `large_blob_key` for how these are generated and used.
+    large_blob_keys: BTreeMap<Vec<u8>, [u8; LARGE_BLOB_KEY_LEN]>,
+}
+
+impl PersistentStore {
+    /// Creates a store in its freshly-reset state: no PIN set yet, and the
+    /// large-blob array holding the empty CBOR array (`0x80`) plus its own
+    /// truncated hash, exactly like a factory-reset authenticator's storage
+    /// would read back on the very first `authenticatorLargeBlobs` `get`.
+    pub fn new(_rng: &mut impl Rng256) -> PersistentStore {
+        PersistentStore {
+            pin_hash: None,
+            pin_retries: 0,
+            large_blob_array: default_large_blob_array(),
+            large_blob_array_write: None,
+            max_msg_size: None,
+            enterprise_attestation: false,
+            always_uv: false,
+            min_pin_length: DEFAULT_MIN_PIN_LENGTH,
+            bio_templates: BTreeMap::new(),
+            next_bio_template_id: 0,
+            large_blob_keys: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the currently set PIN hash, if any.
+    pub fn pin_hash(&self) -> Result<Option<[u8; 16]>, Ctap2StatusCode> {
+        Ok(self.pin_hash)
+    }
+
+    /// Sets the PIN hash and resets the remaining PIN retries.
+    pub fn set_pin(&mut self, pin_hash: &[u8], retries: u8) -> Result<(), Ctap2StatusCode> {
+        let mut hash = [0u8; 16];
+        hash.copy_from_slice(pin_hash);
+        self.pin_hash = Some(hash);
+        self.pin_retries = retries;
+        Ok(())
+    }
+
+    /// Returns up to `byte_count` bytes of the committed large-blob array,
+    /// starting at `offset`.
+    pub fn get_large_blob_array(
+        &self,
+        byte_count: usize,
+        offset: usize,
+    ) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let end = offset
+            .checked_add(byte_count)
+            .filter(|&end| end <= self.large_blob_array.len())
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH)?;
+        Ok(self.large_blob_array[offset..end].to_vec())
+    }
+
+    /// Starts a new large-blob array write, discarding any write left over
+    /// from a previous, aborted sequence. The write only replaces the
+    /// committed array once `commit_large_blob_array_write` succeeds.
+    pub fn start_large_blob_array_write(
+        &mut self,
+        expected_length: usize,
+    ) -> Result<(), Ctap2StatusCode> {
+        self.large_blob_array_write = Some(LargeBlobArrayWrite {
+            buffer: Vec::with_capacity(expected_length),
+        });
+        Ok(())
+    }
+
+    /// Appends `chunk` to the in-progress write started by
+    /// `start_large_blob_array_write`.
+    pub fn write_large_blob_array_chunk(&mut self, chunk: &[u8]) -> Result<(), Ctap2StatusCode> {
+        let write = self
+            .large_blob_array_write
+            .as_mut()
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+        write.buffer.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    /// Discards the in-progress write, leaving the committed array untouched.
+    pub fn abort_large_blob_array_write(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.large_blob_array_write = None;
+        Ok(())
+    }
+
+    /// Replaces the committed large-blob array with the in-progress write.
+    pub fn commit_large_blob_array_write(&mut self) -> Result<(), Ctap2StatusCode> {
+        let write = self
+            .large_blob_array_write
+            .take()
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+        self.large_blob_array = write.buffer;
+        Ok(())
+    }
+
+    /// Sets the `maxMsgSize` `LargeBlobs` fragments on, overriding the
+    /// compile-time `MAX_MSG_SIZE` default until the next factory reset.
+    pub fn set_max_msg_size(&mut self, max_msg_size: usize) -> Result<(), Ctap2StatusCode> {
+        self.max_msg_size = Some(max_msg_size);
+        Ok(())
+    }
+
+    /// Returns the configured `maxMsgSize`, or `None` if the vendor
+    /// subcommand was never used and the compile-time default still applies.
+    pub fn max_msg_size(&self) -> Option<usize> {
+        self.max_msg_size
+    }
+
+    /// Permanently enables the enterprise attestation feature (CTAP 2.1
+    /// section 6.11.1). There is no way to disable it again short of a
+    /// factory reset.
+    pub fn enable_enterprise_attestation(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.enterprise_attestation = true;
+        Ok(())
+    }
+
+    /// Returns whether enterprise attestation is enabled.
+    pub fn enterprise_attestation(&self) -> bool {
+        self.enterprise_attestation
+    }
+
+    /// Toggles the `alwaysUv` option (CTAP 2.1 section 6.11.2).
+    pub fn toggle_always_uv(&mut self) -> Result<(), Ctap2StatusCode> {
+        self.always_uv = !self.always_uv;
+        Ok(())
+    }
+
+    /// Returns the current `alwaysUv` setting.
+    pub fn always_uv(&self) -> bool {
+        self.always_uv
+    }
+
+    /// Sets the `minPINLength` enforced on the next PIN change.
+    pub fn set_min_pin_length(&mut self, min_pin_length: u8) -> Result<(), Ctap2StatusCode> {
+        self.min_pin_length = min_pin_length;
+        Ok(())
+    }
+
+    /// Returns the `minPINLength` enforced on the next PIN change.
+    pub fn min_pin_length(&self) -> u8 {
+        self.min_pin_length
+    }
+
+    /// Reserves a fresh, unique templateId for an enrollment's capture loop.
+    /// The template is not yet enumerable or removable until
+    /// `commit_bio_template` is called with the same id.
+    pub fn allocate_bio_template(&mut self) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let template_id = vec![self.next_bio_template_id];
+        self.next_bio_template_id = self
+            .next_bio_template_id
+            .checked_add(1)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)?;
+        Ok(template_id)
+    }
+
+    /// Releases a templateId that `allocate_bio_template` handed out but that
+    /// was never committed, so an abandoned or superseded enrollment doesn't
+    /// permanently burn through the `u8` id space. Only reclaims `template_id`
+    /// when it is the most recently allocated one; an id superseded by a
+    /// later allocation is left alone, since reusing it could then collide
+    /// with that other enrollment.
+    pub fn release_bio_template(&mut self, template_id: &[u8]) {
+        if let [id] = *template_id {
+            if self.next_bio_template_id.checked_sub(1) == Some(id) {
+                self.next_bio_template_id = id;
+            }
+        }
+    }
+
+    /// Marks `template_id` as a completed enrollment, with no friendly name
+    /// set yet.
+    pub fn commit_bio_template(&mut self, template_id: &[u8]) -> Result<(), Ctap2StatusCode> {
+        self.bio_templates.insert(template_id.to_vec(), None);
+        Ok(())
+    }
+
+    /// Returns every committed template, paired with its friendly name if one
+    /// was set through `set_bio_template_friendly_name`.
+    pub fn enumerate_bio_templates(
+        &self,
+    ) -> Result<Vec<(Vec<u8>, Option<String>)>, Ctap2StatusCode> {
+        Ok(self
+            .bio_templates
+            .iter()
+            .map(|(template_id, friendly_name)| (template_id.clone(), friendly_name.clone()))
+            .collect())
+    }
+
+    /// Sets or replaces the friendly name of an already-committed template.
+    pub fn set_bio_template_friendly_name(
+        &mut self,
+        template_id: &[u8],
+        friendly_name: String,
+    ) -> Result<(), Ctap2StatusCode> {
+        let entry = self
+            .bio_templates
+            .get_mut(template_id)
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+        *entry = Some(friendly_name);
+        Ok(())
+    }
+
+    /// Removes a committed template.
+    pub fn remove_bio_template(&mut self, template_id: &[u8]) -> Result<(), Ctap2StatusCode> {
+        self.bio_templates
+            .remove(template_id)
+            .map(|_| ())
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+    }
+
+    /// Persists a credential's `largeBlobKey`, generated when that credential
+    /// is created with the extension requested.
+    pub fn store_large_blob_key(
+        &mut self,
+        credential_id: &[u8],
+        large_blob_key: [u8; LARGE_BLOB_KEY_LEN],
+    ) {
+        self.large_blob_keys
+            .insert(credential_id.to_vec(), large_blob_key);
+    }
+
+    /// Looks up a credential's `largeBlobKey`, if one was stored for it.
+    pub fn large_blob_key(&self, credential_id: &[u8]) -> Option<[u8; LARGE_BLOB_KEY_LEN]> {
+        self.large_blob_keys.get(credential_id).copied()
+    }
+}
+
+/// The large-blob array of a factory-reset authenticator: the empty CBOR
+/// array (`0x80`) followed by its own truncated SHA-256 hash.
+fn default_large_blob_array() -> Vec<u8> {
+    let empty_cbor_array = vec![0x80];
+    let hash = Sha256::hash(&empty_cbor_array);
+    let mut array = empty_cbor_array;
+    array.extend_from_slice(&hash[..TRUNCATED_HASH_LEN]);
+    array
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::rng256::ThreadRng256;
+
+    #[test]
+    fn test_default_large_blob_array_is_the_empty_array_and_its_hash() {
+        let mut rng = ThreadRng256 {};
+        let persistent_store = PersistentStore::new(&mut rng);
+        assert_eq!(
+            persistent_store.get_large_blob_array(17, 0).unwrap(),
+            vec![
+                0x80, 0x76, 0xbe, 0x8b, 0x52, 0x8d, 0x00, 0x75, 0xf7, 0xaa, 0xe9, 0x8d, 0x6f, 0xa5,
+                0x7a, 0x6d, 0x3c,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_large_blob_array_write_is_only_visible_after_commit() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        persistent_store.start_large_blob_array_write(3).unwrap();
+        persistent_store
+            .write_large_blob_array_chunk(&[0x01, 0x02, 0x03])
+            .unwrap();
+        assert_eq!(
+            persistent_store.get_large_blob_array(17, 0).unwrap(),
+            default_large_blob_array(),
+        );
+        persistent_store.commit_large_blob_array_write().unwrap();
+        assert_eq!(
+            persistent_store.get_large_blob_array(3, 0).unwrap(),
+            vec![0x01, 0x02, 0x03],
+        );
+    }
+
+    #[test]
+    fn test_aborted_large_blob_array_write_leaves_committed_array_untouched() {
+        let mut rng = ThreadRng256 {};
+        let mut persistent_store = PersistentStore::new(&mut rng);
+        persistent_store.start_large_blob_array_write(3).unwrap();
+        persistent_store
+            .write_large_blob_array_chunk(&[0x01, 0x02, 0x03])
+            .unwrap();
+        persistent_store.abort_large_blob_array_write().unwrap();
+        assert_eq!(
+            persistent_store.get_large_blob_array(17, 0).unwrap(),
+            default_large_blob_array(),
+        );
+    }
+}